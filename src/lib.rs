@@ -1,27 +1,53 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
 
+// A stable identifier for a pushed element that stays valid across sifting,
+// so callers can find an element again after its array position has moved
+// (e.g. to lower its priority in Dijkstra without re-pushing it).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle(usize);
+
 // I think the reason the std lib uses this smart pointer for peek mut
 // is to avoid sifting if the value was derefed but not mutated, but that
 // seems like a lot of overhead for avoiding a single sift down since the root
 // should not need to sift down at all if the value was not mutated
-pub struct SmartHeapMutatingPointer<'a, T: PartialOrd> {
+//
+// `needs_sifting` only restoring the heap on Drop is a problem if the guard
+// is mem::forget'd or leaks through a panic: the root could be left smaller
+// than its children and every later pop/peek would silently misbehave. We
+// use leak amplification (the same trick std's PeekMut uses) to make that
+// safe: once deref_mut is called we shrink the heap's logical length down to
+// the single root element, so a forgotten guard just leaks the tail rather
+// than corrupting the heap. On Drop we restore the real length and sift.
+pub struct SmartHeapMutatingPointer<'a, T> {
     heap: &'a mut BinaryHeap<T>,
     needs_sifting: bool,
+    original_len: usize,
 }
 
-impl<T: PartialOrd> Deref for SmartHeapMutatingPointer<'_, T> {
+impl<T> Deref for SmartHeapMutatingPointer<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         self.heap.peek().unwrap()
     }
 }
 
-impl<T: PartialOrd> DerefMut for SmartHeapMutatingPointer<'_, T> {
+impl<T> DerefMut for SmartHeapMutatingPointer<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         // now that we are providing mutable access, we assume we
         // need to sift down the max value just in case its changed
         // to a value that lowers its value
-        self.needs_sifting = true;
+        if !self.needs_sifting {
+            self.needs_sifting = true;
+            self.original_len = self.heap.items.len();
+            // amplify: a heap of length 1 is trivially valid, so even if
+            // this guard never gets dropped, the heap it leaves behind is
+            // still safe to use.
+            unsafe {
+                self.heap.items.set_len(1);
+            }
+        }
         self.heap.items.get_mut(0).unwrap()
     }
 }
@@ -29,19 +55,35 @@ impl<T: PartialOrd> DerefMut for SmartHeapMutatingPointer<'_, T> {
 // when a SmartHeapMutatingPointer goes out of scope and is dropped,
 // we will sift everything back to perfection. This should only happen
 // if DerefMut.deref_mut was called on the smart pointer.
-impl<T: PartialOrd> Drop for SmartHeapMutatingPointer<'_, T> {
+impl<T> Drop for SmartHeapMutatingPointer<'_, T> {
     fn drop(&mut self) {
         if self.needs_sifting {
+            // restore the tail we hid during amplification before sifting,
+            // since sift_down needs the real length to find the children.
+            unsafe {
+                self.heap.items.set_len(self.original_len);
+            }
             self.heap.sift_down(0);
         }
     }
 }
 
-pub struct BinaryHeap<T>
-where
-    T: PartialOrd,
-{
+// Every comparison the heap makes (sift_up, sift_down, delete's tie to
+// sift_down) is routed through `cmp` rather than the `>` operator, so the
+// same heap machinery serves both a max-heap over `PartialOrd` (the default)
+// and an arbitrary ordering supplied via `new_by`/`new_min`. "Greater" as
+// judged by `cmp` is always what bubbles toward the root.
+pub struct BinaryHeap<T> {
     items: Vec<T>,
+    cmp: Box<dyn Fn(&T, &T) -> Ordering>,
+    // Kept in lockstep with `items`: `handle_of_index[i]` is the handle
+    // currently sitting at array index `i`, and `index_of_handle` is its
+    // inverse. Every swap of two slots (in sift_up/sift_down/remove) updates
+    // both, so a handle always resolves to an element's current index in
+    // O(1) instead of the linear scan `delete` has to do.
+    handle_of_index: Vec<Handle>,
+    index_of_handle: HashMap<Handle, usize>,
+    next_handle: usize,
 }
 
 impl<T> BinaryHeap<T>
@@ -49,16 +91,102 @@ where
     T: PartialOrd,
 {
     pub fn new() -> Self {
-        BinaryHeap { items: vec![] }
+        Self::new_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+    }
+
+    // A min-heap is just the max-heap ordering flipped: whichever element
+    // would normally compare smaller is treated as "greater" so it bubbles
+    // to the root instead. Saves callers from wrapping every element in a
+    // `Reverse` newtype.
+    pub fn new_min() -> Self {
+        Self::new_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal))
+    }
+
+    // Building this up with repeated `push` is O(n log n). Every index past
+    // the midpoint is already a trivial one-element subheap, so sifting down
+    // from the last parent up to the root restores the heap property in
+    // O(n) instead.
+    pub fn from_vec(items: Vec<T>) -> Self {
+        let mut heap = Self::new();
+        heap.items = items;
+        for index in 0..heap.items.len() {
+            let handle = heap.alloc_handle();
+            heap.handle_of_index.push(handle);
+            heap.index_of_handle.insert(handle, index);
+        }
+        heap.heapify();
+        heap
+    }
+}
+
+impl<T> BinaryHeap<T> {
+    // The comparator decides what counts as "greater" for the purposes of
+    // sifting, so `cmp(a, b) == Ordering::Greater` means `a` should end up
+    // closer to the root than `b`. Lets callers key on part of `T`, reverse
+    // the natural order for a min-heap, or handle types with no `PartialOrd`
+    // impl at all.
+    pub fn new_by<F>(cmp: F) -> Self
+    where
+        F: Fn(&T, &T) -> Ordering + 'static,
+    {
+        BinaryHeap {
+            items: vec![],
+            cmp: Box::new(cmp),
+            handle_of_index: vec![],
+            index_of_handle: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    fn alloc_handle(&mut self) -> Handle {
+        let handle = Handle(self.next_handle);
+        self.next_handle += 1;
+        handle
+    }
+
+    // The one place that's allowed to move an element between slots. Every
+    // sift/remove goes through this so `handle_of_index`/`index_of_handle`
+    // never drift out of sync with `items`.
+    fn swap_slots(&mut self, i: usize, j: usize) {
+        self.items.swap(i, j);
+        self.handle_of_index.swap(i, j);
+        self.index_of_handle.insert(self.handle_of_index[i], i);
+        self.index_of_handle.insert(self.handle_of_index[j], j);
+    }
+
+    fn heapify(&mut self) {
+        let len = self.items.len();
+        if len < 2 {
+            return;
+        }
+
+        let mut index = len / 2 - 1;
+        loop {
+            self.sift_down(index);
+            if index == 0 {
+                break;
+            }
+            index -= 1;
+        }
     }
 
     pub fn heap(&self) -> impl std::iter::Iterator<Item = &T> {
         self.items.iter()
     }
 
-    pub fn push(&mut self, value: T) {
+    // Drains the heap in pop order (largest first) rather than the raw
+    // array order `heap()` yields, and leaves it empty once exhausted.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        Drain { heap: self }
+    }
+
+    pub fn push(&mut self, value: T) -> Handle {
+        let handle = self.alloc_handle();
         self.items.push(value);
+        self.handle_of_index.push(handle);
+        self.index_of_handle.insert(handle, self.items.len() - 1);
         self.sift_up();
+        handle
     }
 
     pub fn peek(&self) -> Option<&T> {
@@ -72,14 +200,22 @@ where
             Some(SmartHeapMutatingPointer {
                 heap: self,
                 needs_sifting: false,
+                original_len: 0,
             })
         }
     }
 
     pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
         let len = self.items.len();
-        self.items.swap(0, len - 1);
+        self.swap_slots(0, len - 1);
         let largest = self.items.pop();
+        if let Some(handle) = self.handle_of_index.pop() {
+            self.index_of_handle.remove(&handle);
+        }
 
         // now sift the topmost element down until its in the right place
         self.sift_down(0);
@@ -87,21 +223,57 @@ where
         largest
     }
 
-    pub fn delete(&mut self, item: T) -> Option<T> {
-        self.items
-            .iter()
-            .position(|t| item == *t)
-            .and_then(|index| {
-                let len = self.items.len();
-                self.items.swap(index, len - 1);
-                let deleted = self.items.pop();
-                self.sift_down(index);
-                deleted
-            })
+    // Removes whatever is currently sitting at `index`, swapping the last
+    // element into its place the same way `pop` swaps the root. The element
+    // that moves into `index` might belong above or below where the removed
+    // one was, so it's sifted in both directions (only one will actually
+    // move it).
+    fn remove_at(&mut self, index: usize) -> Option<T> {
+        let len = self.items.len();
+        if index >= len {
+            return None;
+        }
+
+        self.swap_slots(index, len - 1);
+        let removed = self.items.pop();
+        if let Some(handle) = self.handle_of_index.pop() {
+            self.index_of_handle.remove(&handle);
+        }
+
+        if index < self.items.len() {
+            self.sift_up_at(index);
+            self.sift_down(index);
+        }
+
+        removed
+    }
+
+    // Removes the element tracked by `handle`, for callers that already hold
+    // one from `push`. The slot is found in O(1) instead of scanning for the
+    // value the way `delete` does; re-sifting the slot afterwards is O(log n).
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        let index = *self.index_of_handle.get(&handle)?;
+        self.remove_at(index)
+    }
+
+    // Overwrites the element behind `handle` and sifts it toward wherever
+    // the new value belongs, without the O(n) scan a pop-and-repush would
+    // cost. Returns the value that was replaced.
+    pub fn change_priority(&mut self, handle: Handle, value: T) -> Option<T> {
+        let index = *self.index_of_handle.get(&handle)?;
+        let old_value = std::mem::replace(&mut self.items[index], value);
+        match (self.cmp)(&self.items[index], &old_value) {
+            Ordering::Greater => self.sift_up_at(index),
+            Ordering::Less => self.sift_down(index),
+            Ordering::Equal => {}
+        }
+        Some(old_value)
     }
 
     pub fn clear(&mut self) {
-        self.items.clear()
+        self.items.clear();
+        self.handle_of_index.clear();
+        self.index_of_handle.clear();
     }
 
     pub fn len(&self) -> usize {
@@ -113,62 +285,161 @@ where
     }
 
     fn sift_up(&mut self) {
-        // greatest value swims to top
-        // let mut index_of_swimmer = self.heap.len() - 1;
-        // let mut index_of_parent = (f64::floor((index_of_swimmer as f64 - 1.0)/2.0)) as usize;
-        // loop {
-        //     let swimmer_value = &self.heap[index_of_swimmer];
-        //     let parent_value = &self.heap[index_of_parent];
-        //     if swimmer_value > parent_value {
-        //         // swap swimmer with parent and then do it again
-        //         self.heap.swap(index_of_parent, index_of_swimmer);
-        //         index_of_swimmer = index_of_parent;
-        //         index_of_parent = (f64::floor((index_of_swimmer as f64 - 1.0)/2.0)) as usize;
-        //     } else {
-        //         break;
-        //     }
-        // }
-
-        let mut index_of_swimmer = self.items.len() - 1;
+        let index = self.items.len() - 1;
+        self.sift_up_at(index);
+    }
+
+    // greatest value swims to top, starting from an arbitrary slot rather
+    // than always the last one, so `change_priority`/`remove` can re-settle
+    // an element wherever it happens to land.
+    fn sift_up_at(&mut self, start_index: usize) {
+        let mut index_of_swimmer = start_index;
         let mut index_of_parent = (f64::floor((index_of_swimmer as f64 - 1.0) / 2.0)) as usize;
-        let mut swimmer_value = &self.items[index_of_swimmer];
-        let mut parent_value = &self.items[index_of_parent];
-        while swimmer_value > parent_value {
+        while (self.cmp)(&self.items[index_of_swimmer], &self.items[index_of_parent])
+            == Ordering::Greater
+        {
             // swap swimmer with parent and then do it again
-            self.items.swap(index_of_parent, index_of_swimmer);
+            self.swap_slots(index_of_parent, index_of_swimmer);
             index_of_swimmer = index_of_parent;
             index_of_parent = (f64::floor((index_of_swimmer as f64 - 1.0) / 2.0)) as usize;
-            swimmer_value = &self.items[index_of_swimmer];
-            parent_value = &self.items[index_of_parent];
         }
     }
 
     fn sift_down(&mut self, start_index: usize) {
+        let len = self.items.len();
+        self.sift_down_within(start_index, len);
+    }
+
+    // Same as `sift_down`, but treats `len` as the end of the heap rather
+    // than `self.items.len()`. `into_sorted_vec` shrinks the logical heap
+    // by one each round while leaving the sorted tail in place, so it needs
+    // sifting that won't wander into the part of the vec it already sorted.
+    fn sift_down_within(&mut self, start_index: usize, len: usize) {
         let left_child_index = 2 * start_index + 1;
         let right_child_index = 2 * start_index + 2;
 
-        let left_child_value = self.items.get(left_child_index);
-        let right_child_value = self.items.get(right_child_index);
-
-        let mut largest_value = self.items.get(start_index);
         let mut largest_index = start_index;
 
-        if left_child_value.is_some() && left_child_value > largest_value {
+        if left_child_index < len
+            && (self.cmp)(&self.items[left_child_index], &self.items[largest_index])
+                == Ordering::Greater
+        {
             largest_index = left_child_index;
-            largest_value = left_child_value;
         }
 
-        if right_child_value.is_some() && right_child_value > largest_value {
+        if right_child_index < len
+            && (self.cmp)(&self.items[right_child_index], &self.items[largest_index])
+                == Ordering::Greater
+        {
             largest_index = right_child_index;
         }
 
         if largest_index != start_index {
-            self.items.swap(start_index, largest_index);
-            self.sift_down(largest_index)
+            self.swap_slots(start_index, largest_index);
+            self.sift_down_within(largest_index, len)
+        }
+    }
+
+    // Consumes the heap and heapsorts it in place: repeatedly swap the max
+    // (index 0) with the last unsorted slot, shrink the unsorted region by
+    // one, and sift the new root down within what remains. Since this is a
+    // max-heap, the largest element lands last each round, so the backing
+    // `Vec` ends up sorted ascending with no extra allocation.
+    pub fn into_sorted_vec(mut self) -> Vec<T> {
+        let mut end = self.items.len();
+        while end > 1 {
+            end -= 1;
+            self.swap_slots(0, end);
+            self.sift_down_within(0, end);
         }
+        self.items
+    }
+}
+
+// Yields elements in pop order (largest first), emptying the heap as it
+// goes. Built by `BinaryHeap::drain`.
+pub struct Drain<'a, T> {
+    heap: &'a mut BinaryHeap<T>,
+}
+
+impl<T> Iterator for Drain<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.heap.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.heap.len(), Some(self.heap.len()))
+    }
+}
+
+impl<T> ExactSizeIterator for Drain<'_, T> {}
+
+// Consumes the heap, yielding elements in pop order (largest first) so
+// `for job in heap` matches the ordering guarantees the rest of the API
+// gives you.
+pub struct IntoIter<T> {
+    heap: BinaryHeap<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.heap.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.heap.len(), Some(self.heap.len()))
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> IntoIterator for BinaryHeap<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { heap: self }
+    }
+}
+
+impl<T> BinaryHeap<T>
+where
+    T: PartialEq,
+{
+    pub fn delete(&mut self, item: T) -> Option<T> {
+        self.items
+            .iter()
+            .position(|t| item == *t)
+            .and_then(|index| self.remove_at(index))
+    }
+}
+
+impl<T> std::iter::FromIterator<T> for BinaryHeap<T>
+where
+    T: PartialOrd,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        BinaryHeap::from_vec(iter.into_iter().collect())
     }
 }
 
+#[test]
+fn test_from_vec_heapifies() {
+    let bh = BinaryHeap::from_vec(vec![1, 2, 5, 4, 3]);
+    assert_eq!(bh.heap().collect::<Vec<&i32>>(), [&5, &4, &1, &2, &3]);
+    assert_eq!(bh.peek(), Some(&5));
+}
+
+#[test]
+fn test_from_iter() {
+    let bh: BinaryHeap<i32> = vec![1, 2, 5, 4, 3].into_iter().collect();
+    assert_eq!(bh.heap().collect::<Vec<&i32>>(), [&5, &4, &1, &2, &3]);
+}
+
 #[test]
 fn test_pushing_and_peeking() {
     let mut bh = BinaryHeap::new();
@@ -317,6 +588,91 @@ fn test_deleting() {
     assert_eq!(bh.heap().collect::<Vec<&i32>>(), [&5, &2, &4, &1]);
 }
 
+#[test]
+fn test_new_min_pops_smallest_first() {
+    let mut bh = BinaryHeap::new_min();
+    bh.push(5);
+    bh.push(1);
+    bh.push(4);
+    bh.push(2);
+    bh.push(3);
+
+    assert_eq!(bh.pop(), Some(1));
+    assert_eq!(bh.pop(), Some(2));
+    assert_eq!(bh.pop(), Some(3));
+    assert_eq!(bh.pop(), Some(4));
+    assert_eq!(bh.pop(), Some(5));
+}
+
+#[test]
+fn test_new_by_keys_on_a_field() {
+    struct Job {
+        priority: i32,
+        id: u32,
+    }
+
+    let mut bh = BinaryHeap::new_by(|a: &Job, b: &Job| a.priority.cmp(&b.priority));
+    bh.push(Job { priority: 1, id: 0 });
+    bh.push(Job { priority: 5, id: 1 });
+    bh.push(Job { priority: 3, id: 2 });
+
+    assert_eq!(bh.pop().map(|j| j.id), Some(1));
+    assert_eq!(bh.pop().map(|j| j.id), Some(2));
+    assert_eq!(bh.pop().map(|j| j.id), Some(0));
+}
+
+#[test]
+fn test_change_priority_raises_and_lowers() {
+    let mut bh = BinaryHeap::new();
+    let low = bh.push(1);
+    bh.push(5);
+    bh.push(2);
+
+    // raising `low` past the current max should put it at the root
+    assert_eq!(bh.change_priority(low, 10), Some(1));
+    assert_eq!(bh.peek(), Some(&10));
+
+    // lowering it back down should sink it out of the root again
+    assert_eq!(bh.change_priority(low, 0), Some(10));
+    assert_eq!(bh.peek(), Some(&5));
+}
+
+#[test]
+fn test_remove_via_handle() {
+    let mut bh = BinaryHeap::new();
+    bh.push(5);
+    let middle = bh.push(3);
+    bh.push(4);
+    bh.push(2);
+    bh.push(1);
+
+    assert_eq!(bh.remove(middle), Some(3));
+    assert_eq!(bh.heap().collect::<Vec<&i32>>(), [&5, &2, &4, &1]);
+
+    // the handle is now stale; removing it again finds nothing
+    assert_eq!(bh.remove(middle), None);
+}
+
+#[test]
+fn test_drain_yields_pop_order_and_empties_the_heap() {
+    let mut bh = BinaryHeap::from_vec(vec![1, 2, 5, 4, 3]);
+    assert_eq!(bh.drain().collect::<Vec<i32>>(), vec![5, 4, 3, 2, 1]);
+    assert!(bh.is_empty());
+}
+
+#[test]
+fn test_into_iterator_yields_pop_order() {
+    let bh = BinaryHeap::from_vec(vec![1, 2, 5, 4, 3]);
+    let popped: Vec<i32> = bh.into_iter().collect();
+    assert_eq!(popped, vec![5, 4, 3, 2, 1]);
+}
+
+#[test]
+fn test_into_sorted_vec() {
+    let bh = BinaryHeap::from_vec(vec![5, 3, 4, 2, 1]);
+    assert_eq!(bh.into_sorted_vec(), vec![1, 2, 3, 4, 5]);
+}
+
 #[test]
 fn test_peek_mut() {
     let mut heap = BinaryHeap::new();
@@ -329,3 +685,21 @@ fn test_peek_mut() {
     }
     assert_eq!(heap.peek(), Some(&2));
 }
+
+#[test]
+fn test_peek_mut_forgotten_guard_leaves_a_valid_heap() {
+    let mut heap = BinaryHeap::new();
+    heap.push(1);
+    heap.push(5);
+    heap.push(2);
+
+    let mut val = heap.peek_mut().unwrap();
+    *val = 0;
+    std::mem::forget(val);
+
+    // the guard was never dropped, so it never got to sift or restore the
+    // tail it hid during amplification; the heap is left truncated to just
+    // its mutated root, which is still a valid (if smaller) heap.
+    assert_eq!(heap.len(), 1);
+    assert_eq!(heap.peek(), Some(&0));
+}